@@ -1,3 +1,5 @@
+// Relies on the `preserve_order` feature of serde_json (backed by indexmap) so that
+// `serde_json::Map` keeps keys in input order instead of sorting them alphabetically.
 use anyhow::{self, Error, Result};
 
 use syntect::easy::HighlightLines;
@@ -5,81 +7,316 @@ use syntect::highlighting::{Style, ThemeSet};
 use syntect::parsing::SyntaxSet;
 use syntect::util::{as_24_bit_terminal_escaped, LinesWithEndings};
 
-use serde_yaml;
+use serde::Deserialize;
 use std::io;
 
+#[derive(PartialEq)]
+enum OutputFormat {
+    Yaml,
+    Json,
+    CanonicalJson,
+}
+
+impl OutputFormat {
+    fn parse(value: &str) -> Result<OutputFormat> {
+        match value {
+            "yaml" => Ok(OutputFormat::Yaml),
+            "json" => Ok(OutputFormat::Json),
+            "canonical-json" => Ok(OutputFormat::CanonicalJson),
+            other => Err(Error::msg(format!("unknown --output format {:?}", other))),
+        }
+    }
+}
+
+fn parse_output_format(args: &[String]) -> Result<OutputFormat> {
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--output" || arg == "-o" {
+            let value = iter
+                .next()
+                .ok_or_else(|| Error::msg("--output requires a value"))?;
+            return OutputFormat::parse(value);
+        }
+        if let Some(value) = arg.strip_prefix("--output=") {
+            return OutputFormat::parse(value);
+        }
+    }
+    Ok(OutputFormat::Yaml)
+}
+
 fn main() -> Result<()> {
-    let input: serde_json::Value = serde_yaml::from_reader(io::stdin())?;
-    let output = parse_input(input);
-    let output = serde_yaml::to_string(&output)?;
+    let args: Vec<String> = std::env::args().collect();
+    let encode = args.iter().any(|arg| arg == "--encode");
+    let format = parse_output_format(&args)?;
+
     // Load these once at the start of your program
     let ps = SyntaxSet::load_defaults_newlines();
     let ts = ThemeSet::load_defaults();
+    let theme = ts.themes.get("base16-ocean.dark").unwrap().to_owned();
+
+    let stdin = io::stdin();
+    for document in serde_yaml::Deserializer::from_reader(stdin.lock()) {
+        let input = serde_json::Value::deserialize(document)?;
+        let output = if encode {
+            encode_input(input)?
+        } else {
+            parse_input(input)?
+        };
+
+        match format {
+            OutputFormat::Yaml => {
+                let rendered = serde_yaml::to_string(&output)?;
+                println!("---");
+                print_highlighted(&rendered, "yaml", &ps, &theme);
+            }
+            OutputFormat::Json => {
+                println!("{}", serde_json::to_string_pretty(&output)?);
+            }
+            OutputFormat::CanonicalJson => {
+                println!("{}", serde_json::to_string(&canonicalize(output))?);
+            }
+        }
+    }
+    Ok(())
+}
 
-    let syntax = ps.find_syntax_by_extension("yaml").unwrap();
-    let mut theme = ts.themes.get("base16-ocean.dark").unwrap().to_owned();
-    let mut h = HighlightLines::new(syntax, &theme);
-    for line in LinesWithEndings::from(output.as_str()) {
+fn print_highlighted(
+    text: &str,
+    extension: &str,
+    ps: &SyntaxSet,
+    theme: &syntect::highlighting::Theme,
+) {
+    let syntax = ps.find_syntax_by_extension(extension).unwrap();
+    let mut h = HighlightLines::new(syntax, theme);
+    for line in LinesWithEndings::from(text) {
         // LinesWithEndings enables use of newlines mode
-        let ranges: Vec<(Style, &str)> = h.highlight_line(line, &ps).unwrap();
+        let ranges: Vec<(Style, &str)> = h.highlight_line(line, ps).unwrap();
         let escaped = as_24_bit_terminal_escaped(&ranges[..], true);
         print!("{}", escaped);
     }
-    return Ok(());
 }
 
-fn parse_input(mut input: serde_json::Value) -> serde_json::Value {
-    if let serde_json::Value::Object(ref mut map) = input {
-        if let Some(kind) = map.get("kind") {
-            if kind == "Secret" {
-                if let Some(data) = map.remove("data") {
-                    let mut string_data = serde_json::Map::new();
-
-                    if let Some(existing_string_data) = map.remove("stringData") {
-                        for (key, value) in existing_string_data.as_object().unwrap() {
-                            string_data.insert(key.to_string(), value.to_owned());
-                        }
-                    }
+/// Recursively sorts object keys in lexicographic byte order so that two
+/// equivalent manifests serialize to byte-identical canonical JSON.
+fn canonicalize(value: serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => {
+            let mut entries: Vec<(String, serde_json::Value)> = map
+                .into_iter()
+                .map(|(key, value)| (key, canonicalize(value)))
+                .collect();
+            entries.sort_by(|(a, _), (b, _)| a.as_bytes().cmp(b.as_bytes()));
+            serde_json::Value::Object(entries.into_iter().collect())
+        }
+        serde_json::Value::Array(items) => {
+            serde_json::Value::Array(items.into_iter().map(canonicalize).collect())
+        }
+        other => other,
+    }
+}
 
-                    for (key, value) in data.as_object().unwrap() {
-                        let decoded = base64::decode(value.as_str().unwrap()).unwrap();
-                        let decoded_string = String::from_utf8(decoded).unwrap();
-                        string_data
-                            .insert(key.to_string(), serde_json::Value::String(decoded_string));
-                    }
-                    map.insert(
-                        "stringData".to_string(),
-                        serde_json::Value::Object(string_data.to_owned()),
-                    );
+fn parse_input(input: serde_json::Value) -> Result<serde_json::Value> {
+    match input {
+        serde_json::Value::Object(mut map) => {
+            if let Some(kind) = map.get("kind").cloned() {
+                if kind == "Secret" {
+                    decode_base64_fields(&mut map, "data", "stringData")?;
+                } else if kind == "ConfigMap" {
+                    decode_base64_fields(&mut map, "binaryData", "data")?;
                 }
-            } else if kind == "List" {
-                if let Some(items) = map.get("items") {
-                    let mut newItems = Vec::new();
-                    for item in items.as_array().unwrap() {
-                        newItems.push(parse_input(item.clone()));
-                    }
-                    map.insert("items".to_string(), serde_json::Value::Array(newItems));
+            }
+
+            let mut out = serde_json::Map::new();
+            for (key, value) in map {
+                out.insert(key, parse_input(value)?);
+            }
+            Ok(serde_json::Value::Object(out))
+        }
+        serde_json::Value::Array(items) => {
+            let mut out = Vec::new();
+            for item in items {
+                out.push(parse_input(item)?);
+            }
+            Ok(serde_json::Value::Array(out))
+        }
+        other => Ok(other),
+    }
+}
+
+/// Base64-decodes every value under `source_key` (e.g. Secret `data`, ConfigMap
+/// `binaryData`) into `dest_key`, merging with whatever already lives there.
+fn decode_base64_fields(
+    map: &mut serde_json::Map<String, serde_json::Value>,
+    source_key: &str,
+    dest_key: &str,
+) -> Result<()> {
+    if let Some(source) = map.shift_remove(source_key) {
+        let mut dest = serde_json::Map::new();
+
+        if let Some(existing_dest) = map.shift_remove(dest_key) {
+            let existing_dest = existing_dest
+                .as_object()
+                .ok_or_else(|| Error::msg(format!("{:?}: expected a mapping", dest_key)))?;
+            for (key, value) in existing_dest {
+                dest.insert(key.to_string(), value.to_owned());
+            }
+        }
+
+        let source = source
+            .as_object()
+            .ok_or_else(|| Error::msg(format!("{:?}: expected a mapping", source_key)))?;
+        for (key, value) in source {
+            let raw = value
+                .as_str()
+                .ok_or_else(|| Error::msg(format!("key {:?}: expected a string value", key)))?;
+            let decoded = base64::decode(raw)
+                .map_err(|e| Error::msg(format!("key {:?}: invalid base64: {}", key, e)))?;
+            let decoded_value = match String::from_utf8(decoded) {
+                Ok(decoded_string) => serde_json::Value::String(decoded_string),
+                Err(e) => serde_json::Value::String(format!("hex:{}", to_hex(e.as_bytes()))),
+            };
+            dest.insert(key.to_string(), decoded_value);
+        }
+        map.insert(dest_key.to_string(), serde_json::Value::Object(dest));
+    }
+    Ok(())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn encode_input(input: serde_json::Value) -> Result<serde_json::Value> {
+    match input {
+        serde_json::Value::Object(mut map) => {
+            if let Some(kind) = map.get("kind").cloned() {
+                // ConfigMap has no inverse of this transform: unlike Secret's
+                // stringData, which exists purely as a write-side convenience the
+                // API server folds into `data`, ConfigMap's `data` and
+                // `binaryData` are both permanent, independently-meaningful
+                // fields. Blindly moving `data` back into `binaryData` here
+                // would wrongly reinterpret legitimate plain-text config values
+                // as binary payloads, so `--encode` only round-trips Secret.
+                if kind == "Secret" {
+                    encode_base64_fields(&mut map, "stringData", "data")?;
                 }
             }
+
+            let mut out = serde_json::Map::new();
+            for (key, value) in map {
+                out.insert(key, encode_input(value)?);
+            }
+            Ok(serde_json::Value::Object(out))
+        }
+        serde_json::Value::Array(items) => {
+            let mut out = Vec::new();
+            for item in items {
+                out.push(encode_input(item)?);
+            }
+            Ok(serde_json::Value::Array(out))
         }
+        other => Ok(other),
     }
-    return input;
+}
+
+/// Base64-encodes every value under `source_key` (e.g. Secret `stringData`)
+/// into `dest_key`, merging with whatever already lives there. The inverse of
+/// `decode_base64_fields`.
+fn encode_base64_fields(
+    map: &mut serde_json::Map<String, serde_json::Value>,
+    source_key: &str,
+    dest_key: &str,
+) -> Result<()> {
+    if let Some(source) = map.shift_remove(source_key) {
+        let mut dest = serde_json::Map::new();
+
+        if let Some(existing_dest) = map.shift_remove(dest_key) {
+            let existing_dest = existing_dest
+                .as_object()
+                .ok_or_else(|| Error::msg(format!("{:?}: expected a mapping", dest_key)))?;
+            for (key, value) in existing_dest {
+                dest.insert(key.to_string(), value.to_owned());
+            }
+        }
+
+        let source = source
+            .as_object()
+            .ok_or_else(|| Error::msg(format!("{:?}: expected a mapping", source_key)))?;
+        for (key, value) in source {
+            let raw = value
+                .as_str()
+                .ok_or_else(|| Error::msg(format!("key {:?}: expected a string value", key)))?;
+            // Values the decoder wrote as `hex:<bytes>` (non-UTF-8 payloads) are
+            // turned back into their original bytes before encoding.
+            let hex_bytes = raw.strip_prefix("hex:").and_then(|hex| {
+                (0..hex.len())
+                    .step_by(2)
+                    .map(|i| hex.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+                    .collect::<Option<Vec<u8>>>()
+            });
+            let encoded = match hex_bytes {
+                Some(bytes) => base64::encode(bytes),
+                None => base64::encode(raw),
+            };
+            dest.insert(key.to_string(), serde_json::Value::String(encoded));
+        }
+        map.insert(dest_key.to_string(), serde_json::Value::Object(dest));
+    }
+    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_canonicalize_sorts_keys_recursively() {
+        let input = serde_json::json!({
+            "b": 1,
+            "a": {
+                "z": 1,
+                "y": 2
+            }
+        });
+
+        let output = canonicalize(input);
+
+        assert_eq!(
+            serde_json::to_string(&output).unwrap(),
+            r#"{"a":{"y":2,"z":1},"b":1}"#
+        );
+    }
+
+    #[test]
+    fn test_parse_output_format() {
+        assert!(matches!(
+            parse_output_format(&["kdecode".to_string()]).unwrap(),
+            OutputFormat::Yaml
+        ));
+        assert!(matches!(
+            parse_output_format(&["kdecode".to_string(), "-o".to_string(), "json".to_string()])
+                .unwrap(),
+            OutputFormat::Json
+        ));
+        assert!(matches!(
+            parse_output_format(&["kdecode".to_string(), "--output=canonical-json".to_string()])
+                .unwrap(),
+            OutputFormat::CanonicalJson
+        ));
+        assert!(parse_output_format(&["kdecode".to_string(), "-o".to_string(), "xml".to_string()])
+            .is_err());
+    }
+
     #[test]
     fn test_parse_input() {
         let input = serde_json::json!({
             "foo": "bar"
         });
 
-        let output = parse_input(input);
-        let outputValue = output.get("foo").unwrap().as_str().unwrap();
+        let output = parse_input(input).unwrap();
+        let output_value = output.get("foo").unwrap().as_str().unwrap();
 
-        assert_eq!(outputValue, "bar");
+        assert_eq!(output_value, "bar");
     }
 
     #[test]
@@ -96,8 +333,8 @@ mod tests {
             }
         });
 
-        let output = parse_input(input);
-        let outputValue = output
+        let output = parse_input(input).unwrap();
+        let output_value = output
             .get("stringData")
             .unwrap()
             .get("key")
@@ -105,7 +342,26 @@ mod tests {
             .as_str()
             .unwrap();
 
-        assert_eq!(outputValue, "value");
+        assert_eq!(output_value, "value");
+    }
+
+    #[test]
+    fn test_decode_secret_preserves_surrounding_key_order() {
+        let input = serde_json::json!({
+            "apiVersion": "v1",
+            "data": {
+                "key": "dmFsdWU="
+            },
+            "kind": "Secret",
+            "metadata": {
+                "name": "example"
+            }
+        });
+
+        let output = parse_input(input).unwrap();
+        let keys: Vec<&str> = output.as_object().unwrap().keys().map(String::as_str).collect();
+
+        assert_eq!(keys, vec!["apiVersion", "kind", "metadata", "stringData"]);
     }
 
     #[test]
@@ -125,8 +381,8 @@ mod tests {
             }
         });
 
-        let output = parse_input(input);
-        let outputValue = output
+        let output = parse_input(input).unwrap();
+        let output_value = output
             .get("stringData")
             .unwrap()
             .get("hello")
@@ -134,7 +390,7 @@ mod tests {
             .as_str()
             .unwrap();
 
-        assert_eq!(outputValue, "world");
+        assert_eq!(output_value, "world");
     }
 
     #[test]
@@ -168,8 +424,8 @@ mod tests {
         }
         );
 
-        let output = parse_input(input);
-        let outputValue = output.get("items").unwrap().as_array().unwrap()[0]
+        let output = parse_input(input).unwrap();
+        let output_value = output.get("items").unwrap().as_array().unwrap()[0]
             .get("stringData")
             .unwrap()
             .get("key")
@@ -177,6 +433,327 @@ mod tests {
             .as_str()
             .unwrap();
 
-        assert_eq!(outputValue, "value");
+        assert_eq!(output_value, "value");
+    }
+
+    #[test]
+    fn test_decode_config_map() {
+        let input = serde_json::json!({
+            "kind": "ConfigMap",
+            "apiVersion": "v1",
+            "metadata": {
+                "name": "example"
+            },
+            "binaryData": {
+                "key": "dmFsdWU="
+            }
+        });
+
+        let output = parse_input(input).unwrap();
+        let output_value = output
+            .get("data")
+            .unwrap()
+            .get("key")
+            .unwrap()
+            .as_str()
+            .unwrap();
+
+        assert_eq!(output_value, "value");
+    }
+
+    #[test]
+    fn test_decode_secret_nested_in_arbitrary_wrapper() {
+        let input = serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "List",
+            "metadata": {},
+            "items": [
+                {
+                    "kind": "Deployment",
+                    "spec": {
+                        "template": {
+                            "secret": {
+                                "apiVersion": "v1",
+                                "kind": "Secret",
+                                "metadata": { "name": "example" },
+                                "data": {
+                                    "key": "dmFsdWU="
+                                }
+                            }
+                        }
+                    }
+                }
+            ]
+        });
+
+        let output = parse_input(input).unwrap();
+        let output_value = output.get("items").unwrap().as_array().unwrap()[0]
+            .get("spec")
+            .unwrap()
+            .get("template")
+            .unwrap()
+            .get("secret")
+            .unwrap()
+            .get("stringData")
+            .unwrap()
+            .get("key")
+            .unwrap()
+            .as_str()
+            .unwrap();
+
+        assert_eq!(output_value, "value");
+    }
+
+    #[test]
+    fn test_decode_secret_non_utf8_falls_back_to_hex() {
+        let input = serde_json::json!({
+            "kind": "Secret",
+            "apiVersion": "v1",
+            "metadata": {
+                "name": "example",
+                "creationTimestamp": null
+            },
+            "data": {
+                "key": "/w=="
+            }
+        });
+
+        let output = parse_input(input).unwrap();
+        let output_value = output
+            .get("stringData")
+            .unwrap()
+            .get("key")
+            .unwrap()
+            .as_str()
+            .unwrap();
+
+        assert_eq!(output_value, "hex:ff");
+    }
+
+    #[test]
+    fn test_decode_secret_invalid_base64_errors() {
+        let input = serde_json::json!({
+            "kind": "Secret",
+            "apiVersion": "v1",
+            "metadata": {
+                "name": "example",
+                "creationTimestamp": null
+            },
+            "data": {
+                "key": "not valid base64!!"
+            }
+        });
+
+        let err = parse_input(input).unwrap_err();
+        assert!(err.to_string().contains("key"));
+    }
+
+    #[test]
+    fn test_decode_secret_non_string_value_errors() {
+        let input = serde_json::json!({
+            "kind": "Secret",
+            "apiVersion": "v1",
+            "metadata": {
+                "name": "example",
+                "creationTimestamp": null
+            },
+            "data": {
+                "key": 12345
+            }
+        });
+
+        let err = parse_input(input).unwrap_err();
+        assert!(err.to_string().contains("key"));
+    }
+
+    #[test]
+    fn test_decode_secret_non_mapping_data_errors() {
+        let input = serde_json::json!({
+            "kind": "Secret",
+            "apiVersion": "v1",
+            "metadata": {
+                "name": "example",
+                "creationTimestamp": null
+            },
+            "data": "not a mapping"
+        });
+
+        let err = parse_input(input).unwrap_err();
+        assert!(err.to_string().contains("data"));
+    }
+
+    #[test]
+    fn test_encode_secret_non_string_value_errors() {
+        let input = serde_json::json!({
+            "kind": "Secret",
+            "apiVersion": "v1",
+            "metadata": {
+                "name": "example",
+                "creationTimestamp": null
+            },
+            "stringData": {
+                "key": 12345
+            }
+        });
+
+        let err = encode_input(input).unwrap_err();
+        assert!(err.to_string().contains("key"));
+    }
+
+    #[test]
+    fn test_encode_secret_non_mapping_string_data_errors() {
+        let input = serde_json::json!({
+            "kind": "Secret",
+            "apiVersion": "v1",
+            "metadata": {
+                "name": "example",
+                "creationTimestamp": null
+            },
+            "stringData": ["not", "a", "mapping"]
+        });
+
+        let err = encode_input(input).unwrap_err();
+        assert!(err.to_string().contains("stringData"));
+    }
+
+    #[test]
+    fn test_encode_secret() {
+        let input = serde_json::json!({
+            "kind": "Secret",
+            "apiVersion": "v1",
+            "metadata": {
+                "name": "example",
+                "creationTimestamp": null
+            },
+            "stringData": {
+                "key": "value"
+            }
+        });
+
+        let output = encode_input(input).unwrap();
+        let output_value = output
+            .get("data")
+            .unwrap()
+            .get("key")
+            .unwrap()
+            .as_str()
+            .unwrap();
+
+        assert_eq!(output_value, "dmFsdWU=");
+    }
+
+    #[test]
+    fn test_encode_secret_data_already_exists() {
+        let input = serde_json::json!({
+            "kind": "Secret",
+            "apiVersion": "v1",
+            "metadata": {
+                "name": "example",
+                "creationTimestamp": null
+            },
+            "stringData": {
+                "key": "value"
+            },
+            "data": {
+                "hello": "d29ybGQ="
+            }
+        });
+
+        let output = encode_input(input).unwrap();
+        let output_value = output
+            .get("data")
+            .unwrap()
+            .get("hello")
+            .unwrap()
+            .as_str()
+            .unwrap();
+
+        assert_eq!(output_value, "d29ybGQ=");
+    }
+
+    #[test]
+    fn test_encode_secret_list() {
+        let input = serde_json::json!({
+          "kind": "List",
+          "metadata": {},
+          "items": [
+            {
+              "apiVersion": "v1",
+              "stringData": {
+                "key": "value"
+              },
+              "kind": "Secret",
+              "metadata": {
+                "creationTimestamp": null,
+                "name": "example"
+              }
+            },
+            {
+              "apiVersion": "v1",
+              "data": {
+                "key": "dmFsdWU="
+              },
+              "kind": "Pod",
+              "metadata": {
+                "name": "example"
+              }
+            }
+          ]
+        }
+        );
+
+        let output = encode_input(input).unwrap();
+        let output_value = output.get("items").unwrap().as_array().unwrap()[0]
+            .get("data")
+            .unwrap()
+            .get("key")
+            .unwrap()
+            .as_str()
+            .unwrap();
+
+        assert_eq!(output_value, "dmFsdWU=");
+    }
+
+    #[test]
+    fn test_encode_secret_nested_in_arbitrary_wrapper() {
+        let input = serde_json::json!({
+            "apiVersion": "v1",
+            "kind": "List",
+            "metadata": {},
+            "items": [
+                {
+                    "kind": "Deployment",
+                    "spec": {
+                        "template": {
+                            "secret": {
+                                "apiVersion": "v1",
+                                "kind": "Secret",
+                                "metadata": { "name": "example" },
+                                "stringData": {
+                                    "key": "value"
+                                }
+                            }
+                        }
+                    }
+                }
+            ]
+        });
+
+        let output = encode_input(input).unwrap();
+        let output_value = output.get("items").unwrap().as_array().unwrap()[0]
+            .get("spec")
+            .unwrap()
+            .get("template")
+            .unwrap()
+            .get("secret")
+            .unwrap()
+            .get("data")
+            .unwrap()
+            .get("key")
+            .unwrap()
+            .as_str()
+            .unwrap();
+
+        assert_eq!(output_value, "dmFsdWU=");
     }
 }